@@ -1,22 +1,35 @@
 use core::str;
 use std::{
     error::Error,
-    io,
-    path::Path,
+    io::{self, Write},
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    pin::Pin,
     process::{Child, Command},
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+    time::{Duration, Instant},
 };
 
 use axum::{
-    extract::{Request, State},
-    http::{HeaderMap, HeaderName, HeaderValue, StatusCode},
+    body::Body,
+    extract::{connect_info::ConnectInfo, Request, State},
+    http::{HeaderMap, HeaderName, HeaderValue, Method, StatusCode},
     response::{IntoResponse, Response},
     Router,
 };
+use brotli::enc::BrotliEncoderParams;
+use bytes::Bytes;
 use clap::Parser;
 use fastcgi_client::{Client, Params};
+use flate2::{write::DeflateEncoder, write::GzEncoder, Compression};
 use futures::TryStreamExt;
 use nix::unistd::Pid;
-use tokio::{fs, net::TcpStream};
+use tokio::{
+    fs,
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    net::{TcpStream, UnixStream},
+};
 use tokio_util::io::StreamReader;
 
 #[derive(Debug, Parser)]
@@ -32,63 +45,415 @@ struct Args {
 
     #[clap(long = "fpm.config_path", default_value_t=String::from("php-fpm.conf"))]
     fpm_config_path: String,
+
+    /// Include php-fpm error diagnostics (parse errors, stderr output) in
+    /// the response body instead of just logging them.
+    #[clap(long)]
+    debug: bool,
+
+    #[clap(long = "compress.gzip", default_value_t = true)]
+    compress_gzip: bool,
+
+    #[clap(long = "compress.deflate", default_value_t = true)]
+    compress_deflate: bool,
+
+    #[clap(long = "compress.brotli", default_value_t = false)]
+    compress_brotli: bool,
+
+    /// Responses smaller than this (in bytes) are sent uncompressed.
+    #[clap(long = "compress.min_size", default_value_t = 1024)]
+    compress_min_size: usize,
+
+    #[clap(
+        long = "compress.content_types",
+        value_delimiter = ',',
+        default_value = "text/html,text/css,text/plain,text/xml,application/javascript,application/json,application/xml,image/svg+xml"
+    )]
+    compress_content_types: Vec<String>,
+
+    /// Docroot served directly for non-PHP requests (static assets);
+    /// anything else falls through to php-fpm.
+    #[clap(long, default_value_t = String::from("pub"))]
+    docroot: String,
+}
+
+#[derive(Clone)]
+struct CompressionConfig {
+    gzip: bool,
+    deflate: bool,
+    brotli: bool,
+    min_size: usize,
+    content_types: Vec<String>,
 }
 
 #[derive(Clone)]
 struct FpmConfig {
     script_path: String,
-    addr: String,
+    script_name: String,
     config_path: String,
+    debug: bool,
+    compression: CompressionConfig,
+    docroot: String,
 }
 
 impl FpmConfig {
     async fn new<P: AsRef<Path>>(
         script_path: P,
         config_path: P,
-        addr: &str,
+        debug: bool,
+        compression: CompressionConfig,
+        docroot: P,
     ) -> Result<Self, Box<dyn Error>> {
-        let script_path = fs::canonicalize(script_path)
+        let script_path = fs::canonicalize(script_path).await?;
+        let script_name = format!(
+            "/{}",
+            script_path
+                .file_name()
+                .ok_or("script_path has no file name")?
+                .to_str()
+                .ok_or("invalid unicode")?
+        );
+        let script_path = script_path.to_str().ok_or("invalid unicode")?.to_string();
+        let config_path = fs::canonicalize(config_path)
             .await?
             .to_str()
             .ok_or("invalid unicode")?
             .to_string();
-        let config_path = fs::canonicalize(config_path)
+        let docroot = fs::canonicalize(docroot)
             .await?
             .to_str()
             .ok_or("invalid unicode")?
             .to_string();
         Ok(Self {
             script_path,
+            script_name,
             config_path,
-            addr: addr.to_string(),
+            debug,
+            compression,
+            docroot,
         })
     }
 }
 
-async fn dispatch_to_fpm(config: &FpmConfig, req: Request) -> Result<Response, Box<dyn Error>> {
-    let stream = TcpStream::connect(&config.addr).await?;
-    let mut client = Client::new_keep_alive(stream);
+// Where to reach php-fpm: a TCP address (`tcp://host:port`, or a bare
+// `host:port` for backwards compatibility) or a Unix domain socket
+// (`unix:/path/to/php-fpm.sock`), the common production setup.
+#[derive(Clone)]
+enum FpmEndpoint {
+    Tcp(String),
+    Unix(String),
+}
+
+impl FpmEndpoint {
+    fn parse(addr: &str) -> Self {
+        if let Some(path) = addr.strip_prefix("unix:") {
+            FpmEndpoint::Unix(path.to_string())
+        } else if let Some(addr) = addr.strip_prefix("tcp://") {
+            FpmEndpoint::Tcp(addr.to_string())
+        } else {
+            FpmEndpoint::Tcp(addr.to_string())
+        }
+    }
+
+    async fn connect(&self) -> io::Result<FpmStream> {
+        match self {
+            FpmEndpoint::Tcp(addr) => Ok(FpmStream::Tcp(TcpStream::connect(addr).await?)),
+            FpmEndpoint::Unix(path) => Ok(FpmStream::Unix(UnixStream::connect(path).await?)),
+        }
+    }
+}
+
+// Either side of a php-fpm connection, so the pool can hold TCP and Unix
+// domain socket clients interchangeably.
+enum FpmStream {
+    Tcp(TcpStream),
+    Unix(UnixStream),
+}
+
+impl AsyncRead for FpmStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            FpmStream::Tcp(s) => Pin::new(s).poll_read(cx, buf),
+            FpmStream::Unix(s) => Pin::new(s).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for FpmStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            FpmStream::Tcp(s) => Pin::new(s).poll_write(cx, buf),
+            FpmStream::Unix(s) => Pin::new(s).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            FpmStream::Tcp(s) => Pin::new(s).poll_flush(cx),
+            FpmStream::Unix(s) => Pin::new(s).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            FpmStream::Tcp(s) => Pin::new(s).poll_shutdown(cx),
+            FpmStream::Unix(s) => Pin::new(s).poll_shutdown(cx),
+        }
+    }
+}
+
+// Caps how many idle connections we'll hold onto, so a request-rate spike
+// can't grow the pool forever (each one is a socket held open to php-fpm).
+const MAX_IDLE_CONNECTIONS: usize = 64;
+
+// php-fpm closes keep-alive connections on its own schedule (pm.max_requests,
+// request_terminate_timeout, a worker recycling, ...), and it does so without
+// telling us. We can't peek at a connection's liveness without consuming
+// `fastcgi_client::Client`'s socket (it doesn't expose one), so instead we
+// track how long a connection has sat idle and simply refuse to hand back
+// one old enough that php-fpm has plausibly already dropped it. This is a
+// heuristic, not a guarantee, which is why `dispatch_to_fpm` also retries
+// once against a fresh connection if a pooled one turns out to be dead.
+const MAX_IDLE_AGE: Duration = Duration::from_secs(30);
+
+// Whether a checked-out client came from the idle pool or was freshly
+// dialed, so callers can decide whether a failure is worth retrying.
+enum Checkout {
+    Pooled(Client<FpmStream>),
+    Fresh(Client<FpmStream>),
+}
+
+impl Checkout {
+    fn into_inner(self) -> Client<FpmStream> {
+        match self {
+            Checkout::Pooled(client) => client,
+            Checkout::Fresh(client) => client,
+        }
+    }
+}
 
-    let mut params = Params::default()
-        .request_method(req.method().to_string())
-        .script_filename(&config.script_path)
-        .script_name("/indx.php")
-        .request_uri("/")
-        .remote_addr("127.0.0.1")
-        .remote_port(12345)
-        .server_addr("127.0.0.1")
-        .server_port(80)
-        .server_name("localhost");
+// Pool of idle, keep-alive fcgi connections to php-fpm. A request checks
+// one out, uses it, and hands it back when done instead of paying a fresh
+// connect round-trip (and losing the point of `new_keep_alive`) every time.
+#[derive(Clone)]
+struct FpmPool {
+    endpoint: FpmEndpoint,
+    idle: Arc<Mutex<Vec<(Client<FpmStream>, Instant)>>>,
+}
 
-    if let Some(v) = req.headers().get(HeaderName::from_static("content-length")) {
-        let len = v.to_str()?.parse::<usize>()?;
-        params = params.content_length(len);
+impl FpmPool {
+    fn new(endpoint: FpmEndpoint) -> Self {
+        Self {
+            endpoint,
+            idle: Arc::new(Mutex::new(Vec::new())),
+        }
     }
 
-    if let Some(v) = req.headers().get(HeaderName::from_static("content-type")) {
-        params = params.content_type(String::from(v.to_str()?));
+    async fn checkout(&self) -> io::Result<Checkout> {
+        while let Some((client, checked_in_at)) = self.idle.lock().unwrap().pop() {
+            if checked_in_at.elapsed() < MAX_IDLE_AGE {
+                return Ok(Checkout::Pooled(client));
+            }
+            // Too old to trust; let it drop (and close) and try the next one.
+        }
+        Ok(Checkout::Fresh(Client::new_keep_alive(
+            self.endpoint.connect().await?,
+        )))
     }
 
+    async fn reconnect(&self) -> io::Result<Client<FpmStream>> {
+        Ok(Client::new_keep_alive(self.endpoint.connect().await?))
+    }
+
+    fn checkin(&self, client: Client<FpmStream>) {
+        let mut idle = self.idle.lock().unwrap();
+        if idle.len() < MAX_IDLE_CONNECTIONS {
+            idle.push((client, Instant::now()));
+        }
+        // Otherwise just drop `client`, closing the connection.
+    }
+}
+
+#[derive(Clone)]
+struct AppState {
+    config: FpmConfig,
+    pool: FpmPool,
+}
+
+// Maps an inbound HTTP header to the CGI `HTTP_*` variable name PHP expects,
+// e.g. `Content-Type` -> `HTTP_CONTENT_TYPE`, `X-Forwarded-For` -> `HTTP_X_FORWARDED_FOR`.
+fn http_param_name(name: &HeaderName) -> String {
+    let mut out = String::with_capacity(5 + name.as_str().len());
+    out.push_str("HTTP_");
+    for c in name.as_str().chars() {
+        out.push(if c == '-' {
+            '_'
+        } else {
+            c.to_ascii_uppercase()
+        });
+    }
+    out
+}
+
+// Picks the best encoding the client accepts (in the order it sent them)
+// that's also enabled in `cfg`, mirroring how a CGI gateway honors
+// `Accept-Encoding`. Entries with `q=0` are treated as excluded.
+fn negotiate_encoding(accept_encoding: &str, cfg: &CompressionConfig) -> Option<&'static str> {
+    accept_encoding
+        .split(',')
+        .filter_map(|item| {
+            let mut parts = item.split(';');
+            let name = parts.next()?.trim().to_ascii_lowercase();
+            let q = parts
+                .find_map(|p| p.trim().strip_prefix("q="))
+                .and_then(|q| q.parse::<f32>().ok())
+                .unwrap_or(1.0);
+            if q > 0.0 {
+                Some(name)
+            } else {
+                None
+            }
+        })
+        .find_map(|name| match name.as_str() {
+            "gzip" if cfg.gzip => Some("gzip"),
+            "br" if cfg.brotli => Some("br"),
+            "deflate" if cfg.deflate => Some("deflate"),
+            _ => None,
+        })
+}
+
+// Whether `body` is worth compressing at all, based on its size and the
+// response's Content-Type.
+fn is_compressible(headers: &HeaderMap, cfg: &CompressionConfig, body_len: usize) -> bool {
+    if body_len < cfg.min_size {
+        return false;
+    }
+    // PHP may have already compressed the body itself (ob_gzhandler and
+    // friends); re-compressing on top of that would double-encode it and
+    // clobber the Content-Encoding it already set.
+    if headers.contains_key(HeaderName::from_static("content-encoding")) {
+        return false;
+    }
+    let Some(content_type) = headers.get(HeaderName::from_static("content-type")) else {
+        return false;
+    };
+    let Ok(content_type) = content_type.to_str() else {
+        return false;
+    };
+    let base = content_type.split(';').next().unwrap_or("").trim();
+    cfg.content_types
+        .iter()
+        .any(|ct| ct.eq_ignore_ascii_case(base))
+}
+
+fn compress_body(encoding: &str, body: &[u8]) -> io::Result<Vec<u8>> {
+    match encoding {
+        "gzip" => {
+            let mut enc = GzEncoder::new(Vec::new(), Compression::default());
+            enc.write_all(body)?;
+            enc.finish()
+        }
+        "deflate" => {
+            let mut enc = DeflateEncoder::new(Vec::new(), Compression::default());
+            enc.write_all(body)?;
+            enc.finish()
+        }
+        "br" => {
+            let mut out = Vec::new();
+            brotli::BrotliCompress(&mut &body[..], &mut out, &BrotliEncoderParams::default())?;
+            Ok(out)
+        }
+        _ => unreachable!("negotiate_encoding only returns encodings we can compress"),
+    }
+}
+
+async fn dispatch_to_fpm(
+    config: &FpmConfig,
+    pool: &FpmPool,
+    peer_addr: SocketAddr,
+    req: Request,
+) -> Result<Response, Box<dyn Error>> {
+    let checkout = pool.checkout().await?;
+    let was_pooled = matches!(checkout, Checkout::Pooled(_));
+    let mut client = checkout.into_inner();
+
+    let accept_encoding = req
+        .headers()
+        .get(HeaderName::from_static("accept-encoding"))
+        .and_then(|v| v.to_str().ok())
+        .map(String::from);
+
+    let method = req.method().to_string();
+    let path = req.uri().path().to_string();
+    let query_string = req.uri().query().unwrap_or("").to_string();
+    let request_uri = match req.uri().path_and_query() {
+        Some(pq) => pq.to_string(),
+        None => path.clone(),
+    };
+    // Cloned up front so we can rebuild `Params` a second time if the first
+    // attempt has to be retried against a reconnected client.
+    let headers: Vec<(HeaderName, HeaderValue)> = req
+        .headers()
+        .iter()
+        .map(|(name, value)| (name.clone(), value.clone()))
+        .collect();
+
+    let build_params = |headers: &[(HeaderName, HeaderValue)]| -> Result<Params, Box<dyn Error>> {
+        let mut params = Params::default()
+            .request_method(method.clone())
+            .script_filename(&config.script_path)
+            .script_name(config.script_name.clone())
+            .request_uri(request_uri.clone())
+            .path_info(path.clone())
+            .query_string(query_string.clone())
+            .remote_addr(peer_addr.ip().to_string())
+            .remote_port(peer_addr.port())
+            .server_addr("127.0.0.1")
+            .server_port(80)
+            .server_name("localhost");
+
+        if let Some((_, v)) = headers
+            .iter()
+            .find(|(n, _)| *n == HeaderName::from_static("content-length"))
+        {
+            params = params.content_length(v.to_str()?.parse::<usize>()?);
+        }
+
+        if let Some((_, v)) = headers
+            .iter()
+            .find(|(n, _)| *n == HeaderName::from_static("content-type"))
+        {
+            params = params.content_type(String::from(v.to_str()?));
+        }
+
+        for (name, value) in headers {
+            if *name == HeaderName::from_static("content-length")
+                || *name == HeaderName::from_static("content-type")
+            {
+                continue;
+            }
+            // Header values are allowed to carry bytes `to_str()` rejects
+            // (ISO-8859-1 bytes >= 0x80 are legal in HTTP but not valid
+            // UTF-8/ASCII), so a single odd header shouldn't 500 the whole
+            // request — fall back to a lossy conversion instead.
+            let value = value
+                .to_str()
+                .map(String::from)
+                .unwrap_or_else(|_| String::from_utf8_lossy(value.as_bytes()).into_owned());
+            params = params.param(http_param_name(name), value);
+        }
+
+        Ok(params)
+    };
+
     // this is some real bullshit, right? this is how you turn a body into an AsyncRead.
     let s = req
         .into_body()
@@ -97,20 +462,211 @@ async fn dispatch_to_fpm(config: &FpmConfig, req: Request) -> Result<Response, B
     let br = StreamReader::new(s);
     futures::pin_mut!(br);
 
-    // This is super stupid. So first of all, we read the entire fcgi response into memory, parse out the headers, make another clone
-    // of the response just to discard the header previous from the original buffer because we need to return something in the response
-    // that it can own.
-    let res = client
-        .execute(fastcgi_client::Request::new(params, &mut br))
-        .await?;
+    let res = match client
+        .execute(fastcgi_client::Request::new(
+            build_params(&headers)?,
+            &mut br,
+        ))
+        .await
+    {
+        Ok(res) => res,
+        // php-fpm can close a keep-alive connection at any point without
+        // telling us (pm.max_requests, a worker recycling, ...), and our
+        // idle-age check is only a heuristic. If the connection we pulled
+        // from the pool turns out to be dead, reconnect and retry exactly
+        // once against a fresh one before giving up. The retry sends the
+        // params again and reuses `br` for the body: safe in practice
+        // because a dead keep-alive connection fails on the very first
+        // write (the PARAMS record, sent before any body bytes), so the
+        // body stream hasn't been touched yet.
+        Err(_) if was_pooled => {
+            client = pool.reconnect().await?;
+            client
+                .execute(fastcgi_client::Request::new(
+                    build_params(&headers)?,
+                    &mut br,
+                ))
+                .await?
+        }
+        Err(err) => return Err(err.into()),
+    };
+    pool.checkin(client);
 
-    let out = res.stdout.ok_or("no stdout")?;
+    let out = Bytes::from(res.stdout.ok_or("no stdout")?);
+    let stderr = res.stderr.unwrap_or_default();
+    let (status, mut headers, mut body) = parse_fpm_response(out, &stderr, config.debug)?;
+
+    if is_compressible(&headers, &config.compression, body.len()) {
+        // The response body we send depends on the request's Accept-Encoding
+        // (compressed or not, and which encoding), so any cache sitting in
+        // front of us needs to key on it too.
+        headers.insert(
+            HeaderName::from_static("vary"),
+            HeaderValue::from_static("Accept-Encoding"),
+        );
+
+        if let Some(encoding) = accept_encoding
+            .as_deref()
+            .and_then(|v| negotiate_encoding(v, &config.compression))
+        {
+            if let Ok(compressed) = compress_body(encoding, &body) {
+                body = Bytes::from(compressed);
+                headers.insert(
+                    HeaderName::from_static("content-encoding"),
+                    HeaderValue::from_static(encoding),
+                );
+                if headers.contains_key(HeaderName::from_static("content-length")) {
+                    headers.insert(
+                        HeaderName::from_static("content-length"),
+                        HeaderValue::from_str(&body.len().to_string())?,
+                    );
+                }
+            }
+        }
+    }
 
-    Ok(parse_fpm_response(&out)?.into_response())
+    // fastcgi_client's `execute` doesn't hand back control until the whole
+    // fcgi response (including the final END_REQUEST record) has arrived,
+    // so by the time we get here `body` is already fully in memory — there's
+    // no way to start forwarding it to the client before php-fpm finishes.
+    // Unless it was compressed above (or replaced by debug diagnostics),
+    // `body` is still a zero-copy slice of the original buffer (see
+    // `parse_fpm_response`), so this at least avoids a second full copy.
+    let mut response = Response::builder().status(status).body(Body::from(body))?;
+    *response.headers_mut() = headers;
+
+    Ok(response)
+}
+
+// Turns a request path into a path relative to a docroot, rejecting `..`
+// components so a request can't escape it.
+fn sanitize_relative_path(req_path: &str) -> Option<PathBuf> {
+    let mut out = PathBuf::new();
+    for seg in req_path.split('/') {
+        match seg {
+            "" | "." => continue,
+            ".." => return None,
+            seg => out.push(seg),
+        }
+    }
+    Some(out)
 }
 
-async fn handler(State(config): State<FpmConfig>, req: Request) -> Response {
-    match dispatch_to_fpm(&config, req).await {
+// Resolves a request path to a static file under `docroot`, mirroring
+// `try_files`: PHP entry points and anything outside the docroot (even via
+// a symlink) are never served this way.
+async fn resolve_static_file(docroot: &Path, req_path: &str) -> Option<PathBuf> {
+    let rel = sanitize_relative_path(req_path)?;
+    if rel.extension().and_then(|e| e.to_str()) == Some("php") {
+        return None;
+    }
+
+    let path = fs::canonicalize(docroot.join(rel)).await.ok()?;
+    if !path.starts_with(docroot) {
+        return None;
+    }
+    // A non-`.php` name can still resolve (via a symlink) to a PHP file.
+    if path.extension().and_then(|e| e.to_str()) == Some("php") {
+        return None;
+    }
+
+    if fs::metadata(&path).await.ok()?.is_file() {
+        Some(path)
+    } else {
+        None
+    }
+}
+
+// Serves a static file off disk, honoring `If-None-Match`/`If-Modified-Since`
+// with a `304 Not Modified`.
+async fn serve_static_file(
+    path: &Path,
+    method: &Method,
+    req_headers: &HeaderMap,
+) -> Result<Response, Box<dyn Error>> {
+    let meta = fs::metadata(path).await?;
+    let modified = meta.modified()?;
+    let modified_secs = modified
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let etag = format!("\"{:x}-{:x}\"", modified_secs, meta.len());
+    let last_modified = httpdate::fmt_http_date(modified);
+
+    let matches_etag = req_headers
+        .get(HeaderName::from_static("if-none-match"))
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v == etag);
+    let not_modified_since = req_headers
+        .get(HeaderName::from_static("if-modified-since"))
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| httpdate::parse_http_date(v).ok())
+        // HTTP dates have whole-second precision, so compare against the
+        // mtime truncated the same way `Last-Modified` is.
+        .is_some_and(|since| std::time::UNIX_EPOCH + Duration::from_secs(modified_secs) <= since);
+
+    let mut response = if matches_etag || not_modified_since {
+        Response::builder()
+            .status(StatusCode::NOT_MODIFIED)
+            .body(Body::empty())?
+    } else {
+        let content_type = mime_guess::from_path(path).first_or_octet_stream();
+        // HEAD's response body is discarded on the wire either way, so don't
+        // pay to read a potentially large file into memory just to throw it
+        // away — send the headers (Content-Length included) with an empty body.
+        let body = if method == Method::HEAD {
+            Body::empty()
+        } else {
+            Body::from(fs::read(path).await?)
+        };
+        Response::builder()
+            .status(StatusCode::OK)
+            .header(
+                HeaderName::from_static("content-type"),
+                content_type.as_ref(),
+            )
+            .header(
+                HeaderName::from_static("content-length"),
+                meta.len().to_string(),
+            )
+            .body(body)?
+    };
+
+    let headers = response.headers_mut();
+    headers.insert(
+        HeaderName::from_static("etag"),
+        HeaderValue::from_str(&etag)?,
+    );
+    headers.insert(
+        HeaderName::from_static("last-modified"),
+        HeaderValue::from_str(&last_modified)?,
+    );
+
+    Ok(response)
+}
+
+async fn handler(
+    State(state): State<AppState>,
+    ConnectInfo(peer_addr): ConnectInfo<SocketAddr>,
+    req: Request,
+) -> Response {
+    // Only GET/HEAD are safe to answer from disk; anything else (POST, PUT,
+    // ...) to a path that happens to match a static asset still needs to
+    // reach php-fpm instead of being served the file out from under it.
+    let is_static_method = req.method() == Method::GET || req.method() == Method::HEAD;
+
+    if is_static_method {
+        if let Some(path) =
+            resolve_static_file(Path::new(&state.config.docroot), req.uri().path()).await
+        {
+            return match serve_static_file(&path, req.method(), req.headers()).await {
+                Ok(res) => res,
+                Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+            };
+        }
+    }
+
+    match dispatch_to_fpm(&state.config, &state.pool, peer_addr, req).await {
         Ok(res) => res,
         Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
     }
@@ -127,24 +683,26 @@ impl HeaderIter<'_> {
 }
 
 impl<'a> Iterator for HeaderIter<'a> {
-    type Item = Result<(HeaderName, HeaderValue), Box<dyn Error>>;
+    // Each line is handed back alongside its parse result so a caller can
+    // recover the raw bytes of a line that isn't a well-formed header.
+    type Item = (&'a [u8], Result<(HeaderName, HeaderValue), Box<dyn Error>>);
     fn next(&mut self) -> Option<Self::Item> {
         if self.data.is_empty() {
             return None;
         }
 
         let sep = vec![b'\r', b'\n'];
-        let data = if let Some(ix) = self.data.windows(sep.len()).position(|w| w == sep) {
-            let data = &self.data[..ix];
+        let line = if let Some(ix) = self.data.windows(sep.len()).position(|w| w == sep) {
+            let line = &self.data[..ix];
             self.data = &self.data[ix + sep.len()..];
-            data
+            line
         } else {
-            let data = self.data;
+            let line = self.data;
             self.data = &[];
-            data
+            line
         };
 
-        Some(parse_fpm_header(data))
+        Some((line, parse_fpm_header(line)))
     }
 }
 
@@ -160,34 +718,102 @@ fn parse_fpm_header(data: &[u8]) -> Result<(HeaderName, HeaderValue), Box<dyn Er
     }
 }
 
-fn parse_fpm_response(data: &[u8]) -> Result<(StatusCode, HeaderMap, Vec<u8>), Box<dyn Error>> {
+// Parses the leading `headers\r\n\r\nbody` prefix of a raw fcgi stdout
+// buffer. The body is returned as a cheaply-cloned slice of `data` rather
+// than a copy, since `data` is already fully in memory by the time this
+// runs (see the comment in `dispatch_to_fpm`) and there's no reason to
+// duplicate it just to strip the header prefix off the front.
+//
+// php-fpm doesn't always emit a clean header block: on a fatal/parse error
+// it prepends lines like `PHP message: PHP Parse error: ... in /path on
+// line N` before the `Status:` line, and separately writes the same kind
+// of diagnostics to `stderr`. Those lines aren't well-formed headers, so
+// rather than aborting on the first one we collect them as a preamble and
+// surface them alongside whatever landed on `stderr`.
+fn parse_fpm_response(
+    data: Bytes,
+    stderr: &[u8],
+    debug: bool,
+) -> Result<(StatusCode, HeaderMap, Bytes), Box<dyn Error>> {
     let sep = vec![b'\r', b'\n', b'\r', b'\n'];
-    let ix = data
-        .windows(sep.len())
-        .position(|w| w == sep)
-        .ok_or("headers not found")?;
+    let Some(ix) = data.windows(sep.len()).position(|w| w == sep) else {
+        // php-fpm didn't emit a well-formed CGI header block at all (e.g. a
+        // fatal error before it got as far as `Status:`/`Content-Type:`).
+        // Bailing with an error here would skip the stderr handling below
+        // entirely and collapse straight to a blank 500 — so still drain
+        // whatever came back and log/surface it the same way the preamble
+        // case does.
+        let mut diagnostics = String::from_utf8_lossy(&data).into_owned();
+        if !stderr.is_empty() {
+            if !diagnostics.is_empty() {
+                diagnostics.push('\n');
+            }
+            diagnostics.push_str(&String::from_utf8_lossy(stderr));
+        }
+        eprintln!("php-fpm: {diagnostics}");
+
+        let body = if debug {
+            Bytes::from(diagnostics)
+        } else {
+            Bytes::new()
+        };
+        return Ok((StatusCode::INTERNAL_SERVER_ERROR, HeaderMap::new(), body));
+    };
 
     let mut status = StatusCode::OK;
+    let mut had_status = false;
     let mut headers = HeaderMap::new();
-    for item in HeaderIter::new(&data[..ix]) {
-        let (name, value) = item?;
-        if name == HeaderName::from_static("status") {
-            let code = str::from_utf8(
-                &value
-                    .as_bytes()
-                    .iter()
-                    .copied()
-                    .take_while(|&c| c.is_ascii_digit())
-                    .collect::<Vec<_>>(),
-            )?
-            .parse::<u16>()?;
-            status = StatusCode::from_u16(code)?;
-        } else {
-            headers.insert(name, value);
+    let mut preamble = Vec::new();
+    for (line, parsed) in HeaderIter::new(&data[..ix]) {
+        if line.is_empty() {
+            continue;
+        }
+        match parsed {
+            Ok((name, value)) if name == HeaderName::from_static("status") => {
+                let code = str::from_utf8(
+                    &value
+                        .as_bytes()
+                        .iter()
+                        .copied()
+                        .take_while(|&c| c.is_ascii_digit())
+                        .collect::<Vec<_>>(),
+                )?
+                .parse::<u16>()?;
+                status = StatusCode::from_u16(code)?;
+                had_status = true;
+            }
+            Ok((name, value)) => {
+                headers.insert(name, value);
+            }
+            Err(_) => {
+                preamble.extend_from_slice(line);
+                preamble.push(b'\n');
+            }
         }
     }
 
-    Ok((status, headers, data[ix + sep.len()..].to_vec()))
+    // No explicit Status but php-fpm logged something to stderr: treat it
+    // as a failed request rather than a quiet 200.
+    if !had_status && !stderr.is_empty() {
+        status = StatusCode::INTERNAL_SERVER_ERROR;
+    }
+
+    if !preamble.is_empty() || !stderr.is_empty() {
+        let mut diagnostics = String::from_utf8_lossy(&preamble).into_owned();
+        if !stderr.is_empty() {
+            if !diagnostics.is_empty() {
+                diagnostics.push('\n');
+            }
+            diagnostics.push_str(&String::from_utf8_lossy(stderr));
+        }
+        eprintln!("php-fpm: {diagnostics}");
+
+        if debug && (status.is_client_error() || status.is_server_error()) {
+            return Ok((status, headers, Bytes::from(diagnostics)));
+        }
+    }
+
+    Ok((status, headers, data.slice(ix + sep.len()..)))
 }
 
 fn run_php_fpm(cfg: &FpmConfig) -> io::Result<Child> {
@@ -207,8 +833,22 @@ fn kill_process_group(proc: &Child) -> Result<(), nix::errno::Errno> {
 async fn main() -> Result<(), Box<dyn Error>> {
     let args = Args::parse();
 
-    let fpm_config =
-        FpmConfig::new(args.fpm_script_path, args.fpm_config_path, &args.fpm_addr).await?;
+    let compression = CompressionConfig {
+        gzip: args.compress_gzip,
+        deflate: args.compress_deflate,
+        brotli: args.compress_brotli,
+        min_size: args.compress_min_size,
+        content_types: args.compress_content_types,
+    };
+
+    let fpm_config = FpmConfig::new(
+        args.fpm_script_path,
+        args.fpm_config_path,
+        args.debug,
+        compression,
+        args.docroot,
+    )
+    .await?;
 
     let fpm_proc = run_php_fpm(&fpm_config)?;
     ctrlc::set_handler(move || {
@@ -216,8 +856,18 @@ async fn main() -> Result<(), Box<dyn Error>> {
         std::process::exit(0);
     })?;
 
-    let app = Router::new().fallback(handler).with_state(fpm_config);
+    let pool = FpmPool::new(FpmEndpoint::parse(&args.fpm_addr));
+    let state = AppState {
+        config: fpm_config,
+        pool,
+    };
+
+    let app = Router::new().fallback(handler).with_state(state);
     let listener = tokio::net::TcpListener::bind(args.addr).await?;
-    axum::serve(listener, app).await?;
+    axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .await?;
     Ok(())
 }